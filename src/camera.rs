@@ -0,0 +1,183 @@
+use cgmath::{InnerSpace, Point3, Vector3};
+use winit::event::{ElementState, MouseButton, VirtualKeyCode};
+
+/// wgpu's clip space is `z: [0, 1]` and cgmath's `perspective` assumes OpenGL's
+/// `z: [-1, 1]`, so every projection needs to be rescaled through this first.
+#[rustfmt::skip]
+pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+/// An eye/target/up camera plus the perspective params needed to build a
+/// view-projection matrix.
+pub struct Camera {
+    pub eye: Point3<f32>,
+    pub target: Point3<f32>,
+    pub up: Vector3<f32>,
+    pub aspect: f32,
+    pub fovy: f32,
+    pub znear: f32,
+    pub zfar: f32,
+}
+
+impl Camera {
+    pub fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        let view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up);
+        let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
+        OPENGL_TO_WGPU_MATRIX * proj * view
+    }
+}
+
+/// The GPU-side mirror of [`Camera`]: a single view-projection matrix,
+/// uploaded to a uniform buffer and bound at group 0.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CameraUniform {
+    pub view_proj: [[f32; 4]; 4],
+}
+
+impl CameraUniform {
+    pub fn new() -> Self {
+        Self {
+            view_proj: cgmath::Matrix4::from_scale(1.0).into(),
+        }
+    }
+
+    pub fn update_view_proj(&mut self, camera: &Camera) {
+        self.view_proj = camera.build_view_projection_matrix().into();
+    }
+}
+
+impl Default for CameraUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Tracks WASD/arrow-key state and left-drag mouse orbiting, applying both
+/// to a [`Camera`] each frame.
+pub struct CameraController {
+    speed: f32,
+    sensitivity: f32,
+    is_forward_pressed: bool,
+    is_backward_pressed: bool,
+    is_left_pressed: bool,
+    is_right_pressed: bool,
+    is_orbiting: bool,
+    last_cursor_pos: Option<(f64, f64)>,
+    yaw_delta: f32,
+    pitch_delta: f32,
+}
+
+impl CameraController {
+    pub fn new(speed: f32) -> Self {
+        Self {
+            speed,
+            sensitivity: 0.005,
+            is_forward_pressed: false,
+            is_backward_pressed: false,
+            is_left_pressed: false,
+            is_right_pressed: false,
+            is_orbiting: false,
+            last_cursor_pos: None,
+            yaw_delta: 0.0,
+            pitch_delta: 0.0,
+        }
+    }
+
+    /// Updates movement state from a keyboard event, returning whether the
+    /// key was one the controller cares about.
+    pub fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
+        let is_pressed = state == ElementState::Pressed;
+        match key {
+            VirtualKeyCode::W | VirtualKeyCode::Up => {
+                self.is_forward_pressed = is_pressed;
+                true
+            }
+            VirtualKeyCode::A | VirtualKeyCode::Left => {
+                self.is_left_pressed = is_pressed;
+                true
+            }
+            VirtualKeyCode::S | VirtualKeyCode::Down => {
+                self.is_backward_pressed = is_pressed;
+                true
+            }
+            VirtualKeyCode::D | VirtualKeyCode::Right => {
+                self.is_right_pressed = is_pressed;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Starts or stops orbiting on a left-button press/release, returning
+    /// whether this was a button the controller cares about.
+    pub fn process_mouse_button(&mut self, button: MouseButton, state: ElementState) -> bool {
+        if button != MouseButton::Left {
+            return false;
+        }
+        self.is_orbiting = state == ElementState::Pressed;
+        if !self.is_orbiting {
+            self.last_cursor_pos = None;
+        }
+        true
+    }
+
+    /// Accumulates a cursor delta into the pending yaw/pitch while orbiting;
+    /// otherwise it only records the cursor position.
+    pub fn process_mouse_motion(&mut self, position: (f64, f64)) {
+        if self.is_orbiting {
+            if let Some((last_x, last_y)) = self.last_cursor_pos {
+                self.yaw_delta += (position.0 - last_x) as f32;
+                self.pitch_delta += (position.1 - last_y) as f32;
+            }
+        }
+        self.last_cursor_pos = Some(position);
+    }
+
+    pub fn update_camera(&mut self, camera: &mut Camera) {
+        let forward = camera.target - camera.eye;
+        let forward_norm = forward.normalize();
+        let forward_mag = forward.magnitude();
+
+        if self.is_forward_pressed && forward_mag > self.speed {
+            camera.eye += forward_norm * self.speed;
+        }
+        if self.is_backward_pressed {
+            camera.eye -= forward_norm * self.speed;
+        }
+
+        let right = forward_norm.cross(camera.up);
+
+        let forward = camera.target - camera.eye;
+        let forward_mag = forward.magnitude();
+
+        if self.is_right_pressed {
+            camera.eye = camera.target - (forward + right * self.speed).normalize() * forward_mag;
+        }
+        if self.is_left_pressed {
+            camera.eye = camera.target - (forward - right * self.speed).normalize() * forward_mag;
+        }
+
+        if self.yaw_delta != 0.0 || self.pitch_delta != 0.0 {
+            let radius_vec = camera.eye - camera.target;
+            let radius = radius_vec.magnitude();
+            let yaw = radius_vec.z.atan2(radius_vec.x) - self.yaw_delta * self.sensitivity;
+            let pitch = (radius_vec.y / radius).asin() - self.pitch_delta * self.sensitivity;
+            let pitch = pitch.clamp(-1.5, 1.5);
+
+            camera.eye = camera.target
+                + Vector3::new(
+                    radius * pitch.cos() * yaw.cos(),
+                    radius * pitch.sin(),
+                    radius * pitch.cos() * yaw.sin(),
+                );
+
+            self.yaw_delta = 0.0;
+            self.pitch_delta = 0.0;
+        }
+    }
+}
@@ -0,0 +1,4 @@
+pub mod camera;
+pub mod renderer;
+pub mod vertex;
+pub mod window;
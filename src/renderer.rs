@@ -0,0 +1,501 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use rayon::prelude::*;
+use wgpu::util::DeviceExt;
+use winit::event::{ElementState, MouseButton, VirtualKeyCode};
+
+use crate::camera::{Camera, CameraController, CameraUniform};
+use crate::vertex::Vertex;
+
+const DEFAULT_SHADER: &str = include_str!("shader.wgsl");
+
+pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+const TRIANGLE_VERTICES: &[Vertex] = &[
+    Vertex {
+        position: [0.0, 0.5, 0.0],
+        color: [1.0, 0.0, 0.0],
+    },
+    Vertex {
+        position: [-0.5, -0.5, 0.0],
+        color: [0.0, 1.0, 0.0],
+    },
+    Vertex {
+        position: [0.5, -0.5, 0.0],
+        color: [0.0, 0.0, 1.0],
+    },
+];
+
+/// The fixed order passes are drawn in within a frame. Declaration order
+/// doubles as draw order, since [`Phase`] derives `Ord`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Phase {
+    Opaque,
+    Transparent,
+    Overlay,
+}
+
+/// Read-only state handed to a [`RenderPass`] while it encodes its commands.
+pub struct FrameContext<'a> {
+    pub device: &'a wgpu::Device,
+    pub view: &'a wgpu::TextureView,
+    pub depth_view: &'a wgpu::TextureView,
+    pub camera_bind_group: &'a wgpu::BindGroup,
+    pub frame_index: usize,
+}
+
+/// A single, independently encodable unit of drawing. Passes in the same
+/// [`Phase`] are encoded in parallel, so implementations must not depend on
+/// draw order within a phase.
+pub trait RenderPass: Send + Sync {
+    fn phase(&self) -> Phase;
+    fn encode(&self, ctx: &FrameContext) -> wgpu::CommandBuffer;
+}
+
+fn create_depth_texture(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+) -> wgpu::TextureView {
+    let size = wgpu::Extent3d {
+        width: config.width.max(1),
+        height: config.height.max(1),
+        depth_or_array_layers: 1,
+    };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Depth Texture"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: DEPTH_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    });
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    shader_src: &str,
+    camera_bind_group_layout: &wgpu::BindGroupLayout,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Shader"),
+        source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(shader_src)),
+    });
+
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Render Pipeline Layout"),
+        bind_group_layouts: &[camera_bind_group_layout],
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Render Pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[Vertex::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
+
+/// The toy triangle draw from the original clear-color demo, now just one
+/// [`RenderPass`] among however many a user registers.
+pub struct TrianglePass {
+    pipeline: wgpu::RenderPipeline,
+    vertex_buffer: wgpu::Buffer,
+    num_vertices: u32,
+}
+
+impl TrianglePass {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        shader_src: &str,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let pipeline = create_render_pipeline(device, format, shader_src, camera_bind_group_layout);
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Vertex Buffer"),
+            contents: bytemuck::cast_slice(TRIANGLE_VERTICES),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        Self {
+            pipeline,
+            vertex_buffer,
+            num_vertices: TRIANGLE_VERTICES.len() as u32,
+        }
+    }
+
+    pub fn with_default_shader(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        Self::new(device, format, DEFAULT_SHADER, camera_bind_group_layout)
+    }
+}
+
+impl RenderPass for TrianglePass {
+    fn phase(&self) -> Phase {
+        Phase::Opaque
+    }
+
+    fn encode(&self, ctx: &FrameContext) -> wgpu::CommandBuffer {
+        let mut encoder = ctx
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Triangle Pass Encoder"),
+            });
+
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Triangle Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: ctx.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: ctx.depth_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, ctx.camera_bind_group, &[]);
+            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            render_pass.draw(0..self.num_vertices, 0..1);
+        }
+
+        encoder.finish()
+    }
+}
+
+/// Wraps a [`wgpu::CommandBuffer`] recorded ahead of time so it can be
+/// threaded through [`Renderer`]'s phase-ordered submission like any other
+/// [`RenderPass`], even though recording it wasn't safe to parallelize (an
+/// imgui draw, say, which needs `&mut` access to non-`Send` UI state).
+pub struct PrebuiltPass {
+    phase: Phase,
+    command_buffer: Mutex<Option<wgpu::CommandBuffer>>,
+}
+
+impl PrebuiltPass {
+    pub fn new(phase: Phase, command_buffer: wgpu::CommandBuffer) -> Self {
+        Self {
+            phase,
+            command_buffer: Mutex::new(Some(command_buffer)),
+        }
+    }
+}
+
+impl RenderPass for PrebuiltPass {
+    fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    fn encode(&self, _ctx: &FrameContext) -> wgpu::CommandBuffer {
+        self.command_buffer
+            .lock()
+            .unwrap()
+            .take()
+            .expect("PrebuiltPass encoded more than once")
+    }
+}
+
+/// Owns the GPU device/queue, the camera/depth state every pass shares, and
+/// a registry of [`RenderPass`]es.
+///
+/// Passes are grouped by [`Phase`] and phases are drawn in their fixed
+/// declaration order; within a phase, passes are encoded in parallel (each
+/// into its own `CommandEncoder`) and their command buffers are submitted
+/// together.
+pub struct Renderer {
+    device: Arc<wgpu::Device>,
+    queue: wgpu::Queue,
+    passes: Vec<Box<dyn RenderPass>>,
+    frames_in_flight: usize,
+    frame_index: usize,
+    pub clear_color: wgpu::Color,
+    camera: Camera,
+    camera_controller: CameraController,
+    camera_uniform: CameraUniform,
+    // One buffer/bind group per in-flight frame so writing next frame's
+    // camera data can't race the GPU still reading the previous frame's.
+    camera_buffers: Vec<wgpu::Buffer>,
+    camera_bind_groups: Vec<wgpu::BindGroup>,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
+    depth_view: wgpu::TextureView,
+}
+
+fn create_camera_resources(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    uniform: CameraUniform,
+    frames_in_flight: usize,
+) -> (Vec<wgpu::Buffer>, Vec<wgpu::BindGroup>) {
+    (0..frames_in_flight)
+        .map(|i| {
+            let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("Camera Buffer {}", i)),
+                contents: bytemuck::cast_slice(&[uniform]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(&format!("Camera Bind Group {}", i)),
+                layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }],
+            });
+            (buffer, bind_group)
+        })
+        .unzip()
+}
+
+impl Renderer {
+    pub fn new(
+        device: Arc<wgpu::Device>,
+        queue: wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Self {
+        let camera = Camera {
+            eye: (0.0, 1.0, 2.0).into(),
+            target: (0.0, 0.0, 0.0).into(),
+            up: cgmath::Vector3::unit_y(),
+            aspect: config.width.max(1) as f32 / config.height.max(1) as f32,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+        };
+        let camera_controller = CameraController::new(0.05);
+        let mut camera_uniform = CameraUniform::new();
+        camera_uniform.update_view_proj(&camera);
+
+        let camera_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Camera Bind Group Layout"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+
+        let frames_in_flight = 2;
+        let (camera_buffers, camera_bind_groups) = create_camera_resources(
+            &device,
+            &camera_bind_group_layout,
+            camera_uniform,
+            frames_in_flight,
+        );
+
+        let depth_view = create_depth_texture(&device, config);
+
+        Self {
+            device,
+            queue,
+            passes: Vec::new(),
+            frames_in_flight,
+            frame_index: 0,
+            clear_color: wgpu::Color::BLACK,
+            camera,
+            camera_controller,
+            camera_uniform,
+            camera_buffers,
+            camera_bind_groups,
+            camera_bind_group_layout,
+            depth_view,
+        }
+    }
+
+    /// Changes how many frames' worth of per-frame GPU resources (currently
+    /// just the camera uniform) are kept in flight, recreating those
+    /// resources and resetting the frame cursor to 0.
+    pub fn with_frames_in_flight(mut self, frames_in_flight: usize) -> Self {
+        let frames_in_flight = frames_in_flight.max(1);
+        let (camera_buffers, camera_bind_groups) = create_camera_resources(
+            &self.device,
+            &self.camera_bind_group_layout,
+            self.camera_uniform,
+            frames_in_flight,
+        );
+        self.camera_buffers = camera_buffers;
+        self.camera_bind_groups = camera_bind_groups;
+        self.frames_in_flight = frames_in_flight;
+        self.frame_index = 0;
+        self
+    }
+
+    pub fn device(&self) -> &Arc<wgpu::Device> {
+        &self.device
+    }
+
+    pub fn queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    pub fn frame_index(&self) -> usize {
+        self.frame_index
+    }
+
+    pub fn camera_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.camera_bind_group_layout
+    }
+
+    pub fn register_pass(&mut self, pass: Box<dyn RenderPass>) {
+        self.passes.push(pass);
+    }
+
+    pub fn clear_passes(&mut self) {
+        self.passes.clear();
+    }
+
+    /// Forwards a keyboard event to the camera controller, returning whether
+    /// it was a movement key the camera cares about.
+    pub fn process_camera_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
+        self.camera_controller.process_keyboard(key, state)
+    }
+
+    /// Forwards a mouse button event to the camera controller, returning
+    /// whether it started or stopped an orbit drag.
+    pub fn process_camera_mouse_button(
+        &mut self,
+        button: MouseButton,
+        state: ElementState,
+    ) -> bool {
+        self.camera_controller.process_mouse_button(button, state)
+    }
+
+    /// Forwards a cursor position to the camera controller, which turns it
+    /// into an orbit delta while a drag is in progress.
+    pub fn process_camera_mouse_motion(&mut self, position: (f64, f64)) {
+        self.camera_controller.process_mouse_motion(position);
+    }
+
+    /// Recreates the depth buffer and updates the camera's aspect ratio to
+    /// match a resized surface.
+    pub fn resize(&mut self, config: &wgpu::SurfaceConfiguration) {
+        self.camera.aspect = config.width.max(1) as f32 / config.height.max(1) as f32;
+        self.depth_view = create_depth_texture(&self.device, config);
+    }
+
+    /// Applies accumulated camera input and re-uploads the view-projection
+    /// matrix for the next frame.
+    pub fn update(&mut self) {
+        self.camera_controller.update_camera(&mut self.camera);
+        self.camera_uniform.update_view_proj(&self.camera);
+        self.queue.write_buffer(
+            &self.camera_buffers[self.frame_index],
+            0,
+            bytemuck::cast_slice(&[self.camera_uniform]),
+        );
+    }
+
+    /// Renders every registered pass plus any `transient_passes` supplied
+    /// just for this frame (the imgui overlay, say, which has to be
+    /// recorded fresh each frame from live UI state rather than registered
+    /// once up front).
+    pub fn render(&mut self, view: &wgpu::TextureView, transient_passes: &[Box<dyn RenderPass>]) {
+        let mut clear_encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Clear Encoder"),
+            });
+        clear_encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Clear Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(self.clear_color),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        self.queue.submit(std::iter::once(clear_encoder.finish()));
+
+        let mut passes_by_phase: BTreeMap<Phase, Vec<&dyn RenderPass>> = BTreeMap::new();
+        for pass in self.passes.iter().chain(transient_passes.iter()) {
+            passes_by_phase
+                .entry(pass.phase())
+                .or_default()
+                .push(pass.as_ref());
+        }
+
+        let ctx = FrameContext {
+            device: &self.device,
+            view,
+            depth_view: &self.depth_view,
+            camera_bind_group: &self.camera_bind_groups[self.frame_index],
+            frame_index: self.frame_index,
+        };
+
+        for passes in passes_by_phase.into_values() {
+            let command_buffers: Vec<wgpu::CommandBuffer> =
+                passes.par_iter().map(|pass| pass.encode(&ctx)).collect();
+            self.queue.submit(command_buffers);
+        }
+
+        self.frame_index = (self.frame_index + 1) % self.frames_in_flight;
+    }
+}
@@ -1,37 +1,100 @@
-use rand::Rng;
+use std::sync::Arc;
+use std::time::Instant;
+
 use wgpu::RequestAdapterOptions;
 use winit::{
-    event::{ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent},
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
 };
 
+use crate::renderer::{Phase, PrebuiltPass, RenderPass, Renderer, TrianglePass};
+
+/// The default debug overlay: a background color picker plus an FPS readout.
+fn default_ui_callback(ui: &imgui::Ui, clear_color: &mut wgpu::Color) {
+    ui.window("debug")
+        .size([240.0, 120.0], imgui::Condition::FirstUseEver)
+        .build(|| {
+            let mut color = [
+                clear_color.r as f32,
+                clear_color.g as f32,
+                clear_color.b as f32,
+            ];
+            if imgui::ColorEdit::new("background", &mut color).build(ui) {
+                clear_color.r = color[0] as f64;
+                clear_color.g = color[1] as f64;
+                clear_color.b = color[2] as f64;
+            }
+            ui.text(format!("{:.1} fps", ui.io().framerate));
+        });
+}
+
+/// Configuration used to bring up a [`Window`]'s backing GPU context.
+///
+/// Defaults to [`wgpu::Backends::all()`] so the crate picks a working
+/// backend (Vulkan/DX12/Metal natively, WebGPU/WebGL on web) instead of
+/// assuming a single platform.
+pub struct WindowConfig {
+    pub backends: wgpu::Backends,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            backends: wgpu::Backends::all(),
+        }
+    }
+}
+
 pub struct Window {
     window: winit::window::Window,
-    background_color: wgpu::Color,
     surface: wgpu::Surface,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
+    renderer: Renderer,
     config: wgpu::SurfaceConfiguration,
     size: winit::dpi::PhysicalSize<u32>,
+    imgui_context: imgui::Context,
+    imgui_platform: imgui_winit_support::WinitPlatform,
+    imgui_renderer: imgui_wgpu::Renderer,
+    last_frame: Instant,
+    ui_callback: Box<dyn FnMut(&imgui::Ui, &mut wgpu::Color)>,
     _event_loop: Option<EventLoop<()>>,
 }
 
-fn random_rgb_val() -> f64 {
-    rand::thread_rng().gen_range(0.0..1.0)
-}
-
 impl Window {
     pub async fn new() -> Self {
-        Window::configure(EventLoop::new()).await
+        #[cfg(target_arch = "wasm32")]
+        {
+            std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+            console_log::init_with_level(log::Level::Warn).expect("failed to init logger");
+        }
+
+        Window::new_with_config(WindowConfig::default()).await
     }
 
-    async fn configure(event_loop: EventLoop<()>) -> Self {
+    pub async fn new_with_config(config: WindowConfig) -> Self {
+        Window::configure(EventLoop::new(), config).await
+    }
+
+    async fn configure(event_loop: EventLoop<()>, window_config: WindowConfig) -> Self {
         let window = {
             let w = winit::window::Window::new(&event_loop).unwrap();
             w.set_title(":P");
             w
         };
-        let instance = wgpu::Instance::new(wgpu::Backend::Metal.into());
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            use winit::platform::web::WindowExtWebSys;
+            web_sys::window()
+                .and_then(|win| win.document())
+                .and_then(|doc| doc.body())
+                .and_then(|body| {
+                    body.append_child(&web_sys::Element::from(window.canvas()))
+                        .ok()
+                })
+                .expect("couldn't append canvas to document body");
+        }
+
+        let instance = wgpu::Instance::new(window_config.backends);
         let surface = unsafe { instance.create_surface(&window) };
         let adapter = instance
             .request_adapter(&RequestAdapterOptions {
@@ -42,17 +105,24 @@ impl Window {
             .await
             .expect("failed to bind to adapter");
 
+        let limits = if cfg!(target_arch = "wasm32") {
+            wgpu::Limits::downlevel_webgl2_defaults()
+        } else {
+            wgpu::Limits::default()
+        };
+
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: None,
                     features: wgpu::Features::empty(),
-                    limits: wgpu::Limits::default(),
+                    limits,
                 },
                 None,
             )
             .await
             .expect("Failed to create device");
+        let device = Arc::new(device);
 
         let size = window.inner_size();
 
@@ -65,19 +135,51 @@ impl Window {
             format: surface.get_supported_formats(&adapter)[0],
         };
         surface.configure(&device, &config);
+
+        let mut imgui_context = imgui::Context::create();
+        let mut imgui_platform = imgui_winit_support::WinitPlatform::init(&mut imgui_context);
+        imgui_platform.attach_window(
+            imgui_context.io_mut(),
+            &window,
+            imgui_winit_support::HiDpiMode::Default,
+        );
+        imgui_context
+            .fonts()
+            .add_font(&[imgui::FontSource::DefaultFontData { config: None }]);
+        let imgui_renderer = imgui_wgpu::Renderer::new(
+            &mut imgui_context,
+            &device,
+            &queue,
+            imgui_wgpu::RendererConfig {
+                texture_format: config.format,
+                ..Default::default()
+            },
+        );
+
+        let mut renderer = Renderer::new(device.clone(), queue, &config);
+        renderer.clear_color = wgpu::Color {
+            r: 0.1,
+            g: 0.2,
+            b: 0.3,
+            a: 1.0,
+        };
+        renderer.register_pass(Box::new(TrianglePass::with_default_shader(
+            &device,
+            config.format,
+            renderer.camera_bind_group_layout(),
+        )));
+
         Window {
             window,
             surface,
-            device,
-            queue,
+            renderer,
             config,
             size,
-            background_color: wgpu::Color {
-                r: 0.1,
-                g: 0.2,
-                b: 0.3,
-                a: 1.0,
-            },
+            imgui_context,
+            imgui_platform,
+            imgui_renderer,
+            last_frame: Instant::now(),
+            ui_callback: Box::new(default_ui_callback),
             _event_loop: Some(event_loop),
         }
     }
@@ -86,7 +188,30 @@ impl Window {
         self.size = new_size;
         self.config.width = new_size.width;
         self.config.height = new_size.height;
-        self.surface.configure(&self.device, &self.config);
+        self.surface.configure(self.renderer.device(), &self.config);
+        self.renderer.resize(&self.config);
+    }
+
+    /// Swaps the running scene for a freshly compiled WGSL shader, replacing
+    /// the registered passes with a single triangle pass built from it.
+    pub fn set_shader(&mut self, shader_src: &str) {
+        let pass = TrianglePass::new(
+            self.renderer.device(),
+            self.config.format,
+            shader_src,
+            self.renderer.camera_bind_group_layout(),
+        );
+        self.renderer.clear_passes();
+        self.renderer.register_pass(Box::new(pass));
+    }
+
+    /// Overrides the widgets drawn in the debug overlay each frame. Defaults
+    /// to a background color picker and an FPS counter.
+    pub fn set_ui_callback(
+        &mut self,
+        callback: impl FnMut(&imgui::Ui, &mut wgpu::Color) + 'static,
+    ) {
+        self.ui_callback = Box::new(callback);
     }
 
     fn input(&mut self, _event: &WindowEvent) -> bool {
@@ -94,7 +219,58 @@ impl Window {
     }
 
     fn update(&mut self) {
-        // todo!()
+        self.renderer.update();
+    }
+
+    /// Builds this frame's imgui draw data and records it into a standalone
+    /// command buffer, ready to hand to [`Renderer`] as an [`Phase::Overlay`]
+    /// pass. Recording has to happen here, up front and off the rayon pool,
+    /// because imgui's context/platform state isn't `Send`.
+    fn record_overlay_pass(&mut self, view: &wgpu::TextureView) -> PrebuiltPass {
+        let now = Instant::now();
+        self.imgui_context
+            .io_mut()
+            .update_delta_time(now - self.last_frame);
+        self.last_frame = now;
+
+        self.imgui_platform
+            .prepare_frame(self.imgui_context.io_mut(), &self.window)
+            .expect("failed to prepare imgui frame");
+        let ui = self.imgui_context.frame();
+        (self.ui_callback)(&ui, &mut self.renderer.clear_color);
+        self.imgui_platform.prepare_render(&ui, &self.window);
+        let draw_data = self.imgui_context.render();
+
+        let mut encoder =
+            self.renderer
+                .device()
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Imgui Encoder"),
+                });
+        {
+            let mut imgui_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Imgui Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            self.imgui_renderer
+                .render(
+                    draw_data,
+                    self.renderer.queue(),
+                    self.renderer.device(),
+                    &mut imgui_pass,
+                )
+                .expect("imgui render failed");
+        }
+
+        PrebuiltPass::new(Phase::Overlay, encoder.finish())
     }
 
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
@@ -102,84 +278,100 @@ impl Window {
         let view = output
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Render Encoder"),
-            });
 
-        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: Some("Render Pass"),
-            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &view,
-                resolve_target: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(self.background_color),
-                    store: true,
-                },
-            })],
-            depth_stencil_attachment: None,
-        });
+        let overlay_pass: Box<dyn RenderPass> = Box::new(self.record_overlay_pass(&view));
+        self.renderer.render(&view, &[overlay_pass]);
 
-        // submit will accept anything that implements IntoIter
-        self.queue.submit(std::iter::once(encoder.finish()));
         output.present();
 
         Ok(())
     }
 
+    /// The browser only reports a canvas size once it's mounted in the DOM,
+    /// so `inner_size` is `0x0` the moment [`Window::configure`] runs. Read
+    /// it back from the canvas itself now that the event loop is up.
+    #[cfg(target_arch = "wasm32")]
+    fn canvas_size(&self) -> winit::dpi::PhysicalSize<u32> {
+        use winit::platform::web::WindowExtWebSys;
+        let canvas = self.window.canvas();
+        winit::dpi::PhysicalSize::new(canvas.width(), canvas.height())
+    }
+
     pub async fn spawn(mut self) -> ! {
+        #[cfg(target_arch = "wasm32")]
+        {
+            let canvas_size = self.canvas_size();
+            self.resize(canvas_size);
+        }
+
         self._event_loop
             .take()
             .expect("event loop not found :(")
-            .run(move |event, _, control_flow| match event {
-                Event::WindowEvent {
-                    ref event,
-                    window_id,
-                } if window_id == self.window.id() => match event {
-                    WindowEvent::CloseRequested
-                    | WindowEvent::KeyboardInput {
-                        input:
-                            KeyboardInput {
-                                state: ElementState::Pressed,
-                                virtual_keycode: Some(VirtualKeyCode::Escape),
-                                ..
-                            },
-                        ..
-                    } => *control_flow = ControlFlow::Exit,
-                    WindowEvent::Resized(new_size) => {
-                        self.resize(*new_size);
-                    }
-                    WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                        self.resize(**new_inner_size);
-                    }
-                    WindowEvent::MouseInput { button, state, .. } => {
-                        if matches!(button, MouseButton::Left)
-                            && matches!(state, ElementState::Pressed)
-                        {
-                            self.background_color = wgpu::Color {
-                                r: random_rgb_val(),
-                                g: random_rgb_val(),
-                                b: random_rgb_val(),
-                                a: 1.0,
-                            };
+            .run(move |event, _, control_flow| {
+                self.imgui_platform
+                    .handle_event(self.imgui_context.io_mut(), &self.window, &event);
+
+                match event {
+                    Event::WindowEvent {
+                        ref event,
+                        window_id,
+                    } if window_id == self.window.id() => match event {
+                        WindowEvent::CloseRequested
+                        | WindowEvent::KeyboardInput {
+                            input:
+                                KeyboardInput {
+                                    state: ElementState::Pressed,
+                                    virtual_keycode: Some(VirtualKeyCode::Escape),
+                                    ..
+                                },
+                            ..
+                        } => *control_flow = ControlFlow::Exit,
+                        WindowEvent::Resized(new_size) => {
+                            self.resize(*new_size);
+                        }
+                        WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                            self.resize(**new_inner_size);
+                        }
+                        WindowEvent::KeyboardInput {
+                            input:
+                                KeyboardInput {
+                                    state,
+                                    virtual_keycode: Some(keycode),
+                                    ..
+                                },
+                            ..
+                        } => {
+                            self.renderer.process_camera_keyboard(*keycode, *state);
+                        }
+                        WindowEvent::MouseInput { button, state, .. } => {
+                            self.renderer.process_camera_mouse_button(*button, *state);
+                        }
+                        WindowEvent::CursorMoved { position, .. } => {
+                            self.renderer
+                                .process_camera_mouse_motion((position.x, position.y));
+                        }
+                        _ => {}
+                    },
+                    Event::RedrawRequested(window_id) if window_id == self.window.id() => {
+                        self.update();
+                        match self.render() {
+                            Ok(_) => {}
+                            Err(wgpu::SurfaceError::Lost) => self.resize(self.size),
+                            Err(wgpu::SurfaceError::OutOfMemory) => {
+                                *control_flow = ControlFlow::Exit
+                            }
+                            // The browser can report a spurious timeout while the tab is
+                            // backgrounded or the canvas is resizing; just skip the frame.
+                            #[cfg(target_arch = "wasm32")]
+                            Err(wgpu::SurfaceError::Timeout) => {}
+                            Err(e) => eprintln!("{:?}", e),
                         }
                     }
-                    _ => {}
-                },
-                Event::RedrawRequested(window_id) if window_id == self.window.id() => {
-                    self.update();
-                    match self.render() {
-                        Ok(_) => {}
-                        Err(wgpu::SurfaceError::Lost) => self.resize(self.size),
-                        Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
-                        Err(e) => eprintln!("{:?}", e),
+                    Event::MainEventsCleared => {
+                        self.window.request_redraw();
                     }
+                    _ => {}
                 }
-                Event::MainEventsCleared => {
-                    self.window.request_redraw();
-                }
-                _ => {}
             })
     }
 }